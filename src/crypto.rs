@@ -0,0 +1,51 @@
+use aes_gcm::{
+    aead::{Aead, KeyInit, OsRng, rand_core::RngCore},
+    Aes256Gcm, Key, Nonce,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+/// Bumped whenever the stored payload format changes, so future algorithm changes
+/// stay decodable alongside values encrypted under the current scheme.
+const ENCRYPTION_VERSION_V1: u8 = 1;
+const NONCE_LEN: usize = 12;
+
+/// Encrypts `plaintext` under a fresh random 96-bit nonce and returns
+/// `base64(version_byte || nonce || ciphertext)`, ready to store in the `value` column.
+pub fn encrypt_value(key: &[u8; 32], plaintext: &str) -> String {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+
+    let mut nonce_bytes = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce_bytes);
+    let nonce = Nonce::from_slice(&nonce_bytes);
+
+    let ciphertext = cipher
+        .encrypt(nonce, plaintext.as_bytes())
+        .expect("AES-256-GCM encryption failed");
+
+    let mut payload = Vec::with_capacity(1 + NONCE_LEN + ciphertext.len());
+    payload.push(ENCRYPTION_VERSION_V1);
+    payload.extend_from_slice(&nonce_bytes);
+    payload.extend_from_slice(&ciphertext);
+
+    STANDARD.encode(payload)
+}
+
+/// Decrypts a value produced by `encrypt_value`. Any failure - truncated payload,
+/// unknown version byte, or a failed AEAD tag check - collapses to a single opaque
+/// error so a tampered or corrupted row can't be used as a decryption oracle.
+pub fn decrypt_value(key: &[u8; 32], stored: &str) -> Result<String, ()> {
+    let payload = STANDARD.decode(stored).map_err(|_| ())?;
+    if payload.len() < 1 + NONCE_LEN {
+        return Err(());
+    }
+    if payload[0] != ENCRYPTION_VERSION_V1 {
+        return Err(());
+    }
+
+    let nonce = Nonce::from_slice(&payload[1..1 + NONCE_LEN]);
+    let ciphertext = &payload[1 + NONCE_LEN..];
+
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let plaintext = cipher.decrypt(nonce, ciphertext).map_err(|_| ())?;
+    String::from_utf8(plaintext).map_err(|_| ())
+}
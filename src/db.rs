@@ -1,5 +1,6 @@
-use sqlx::{postgres::PgPoolOptions, PgPool};
+use sqlx::{postgres::{PgListener, PgPoolOptions}, PgPool};
 use std::env;
+use tokio::sync::broadcast;
 
 pub async fn create_pool(database_url: &str) -> PgPool {
     PgPoolOptions::new()
@@ -9,6 +10,35 @@ pub async fn create_pool(database_url: &str) -> PgPool {
         .expect("Failed to create database connection pool")
 }
 
+/// Holds a dedicated `PgListener` on the `key_changed` channel (populated by the
+/// `keys_notify_insert`/`keys_notify_update` triggers) and forwards every notification payload - the name
+/// of the key that changed - onto a `tokio::sync::broadcast` channel that handlers
+/// can cheaply subscribe to.
+pub async fn spawn_key_change_listener(database_url: &str, tx: broadcast::Sender<String>) {
+    let mut listener = PgListener::connect(database_url)
+        .await
+        .expect("Failed to connect key change listener");
+
+    listener
+        .listen("key_changed")
+        .await
+        .expect("Failed to LISTEN on key_changed channel");
+
+    tokio::spawn(async move {
+        loop {
+            match listener.recv().await {
+                Ok(notification) => {
+                    // No receivers is not an error, it just means nobody is currently polling
+                    let _ = tx.send(notification.payload().to_string());
+                },
+                Err(e) => {
+                    eprintln!("key_changed listener error: {}", e);
+                }
+            }
+        }
+    });
+}
+
 pub async fn delete_unused_keys(pool: &PgPool, after: &String) {
     let result = sqlx::query(&format!("DELETE FROM keys WHERE last_accessed < CURRENT_TIMESTAMP - INTERVAL '{}'", after).to_string())
         .execute(pool)
@@ -17,6 +47,7 @@ pub async fn delete_unused_keys(pool: &PgPool, after: &String) {
     match result {
         Ok(result) => {
             let affected_rows = result.rows_affected();
+            metrics::counter!("kvdb_cleanup_deletions_total").increment(affected_rows);
             println!("Unused key cleanup complete, {} rows affected.", affected_rows)
         },
         Err(e) => {
@@ -38,3 +69,9 @@ pub fn get_environment_variable_or_default(key: &str, default: &str) -> String {
         default.to_string()
     })
 }
+
+/// Like `get_environment_variable`, but returns `None` instead of exiting when unset,
+/// for variables like `ENCRYPTION_KEY` that are genuinely optional.
+pub fn get_optional_environment_variable(key: &str) -> Option<String> {
+    env::var(key).ok()
+}
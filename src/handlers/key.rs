@@ -1,18 +1,57 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
 use axum::{extract::{Query, State}, response::{IntoResponse, Response}, Json};
 use chrono::Utc;
+use tokio::sync::broadcast;
+
+use crate::models::{AppState, Key, KeyCreateInput, KeyCreateResponse, KeyGetInput, KeyGetResponse, KeyUpdateInput, KeyUpdateResponse, KeyConflictResponse, KeyDeleteInput, KeyDeleteResponse, ErrorResponse, BatchItemResult, BatchReadResponse, BatchDeleteResponse, KeyPollInput, KeyPollResponse, KeyListInput, KeyListResponse};
+use crate::telemetry::record_duration;
 
-use crate::models::{AppState, Key, KeyCreateInput, KeyCreateResponse, KeyGetInput, KeyGetResponse, KeyUpdateInput, KeyUpdateResponse, KeyDeleteInput, KeyDeleteResponse, ErrorResponse};
+const DEFAULT_POLL_TIMEOUT_MS: u64 = 30_000;
+const MAX_POLL_TIMEOUT_MS: u64 = 120_000;
+
+const DEFAULT_LIST_LIMIT: i64 = 100;
+const MAX_LIST_LIMIT: i64 = 1000;
 
 fn get_name_or_generate(input: &Option<String>) -> String {
     input.clone().unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
 }
 
+// Encrypts `value` with the configured ENCRYPTION_KEY before it's written to the
+// `value` column, or passes it through unchanged when encryption is disabled.
+fn encrypt_if_enabled(app_state: &AppState, value: String) -> String {
+    match &app_state.config.encryption_key {
+        Some(key) => crate::crypto::encrypt_value(key, &value),
+        None => value,
+    }
+}
+
+// Decrypts a value read back from the `value` column. Failures (tampering,
+// corruption, or a key rotated out from under existing rows) are collapsed to a
+// generic 500 so the error can't be used as a decryption oracle.
+fn decrypt_if_enabled(app_state: &AppState, value: String) -> Result<String, Response> {
+    match &app_state.config.encryption_key {
+        Some(key) => crate::crypto::decrypt_value(key, &value).map_err(|_| {
+            (
+                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "Failed to read value".to_string(),
+                    success: false,
+                })
+            ).into_response()
+        }),
+        None => Ok(value),
+    }
+}
+
 // Handler to create a new key
 #[axum::debug_handler]
 pub async fn new_key_handler(
     State(app_state): State<AppState>,
     Json(input): Json<KeyCreateInput>,
 ) -> Result<Json<KeyCreateResponse>, Response> {
+    let started = Instant::now();
     let pool = &app_state.pool;
 
     // Check if value is shorter than the maximum allowed length
@@ -55,17 +94,18 @@ pub async fn new_key_handler(
     // Check if name and roname are provided, if not, generate random names
     let name = get_name_or_generate(&input.name);
     let roname = get_name_or_generate(&input.name_readonly);
+    let value = encrypt_if_enabled(&app_state, input.value.unwrap_or_default());
 
     let row = sqlx::query_as::<_, Key>(
         r#"
         INSERT INTO keys (name, roname, value, last_accessed)
         VALUES ($1, $2, $3, $4)
-        RETURNING name, roname, value, last_accessed
+        RETURNING name, roname, value, last_accessed, version, siblings
         "#
     )
     .bind(name)
     .bind(roname)
-    .bind(input.value.unwrap_or_default())
+    .bind(value)
     .bind(Utc::now())
     .fetch_one(pool)
     .await
@@ -79,6 +119,9 @@ pub async fn new_key_handler(
         ).into_response()
     })?;
 
+    metrics::counter!("kvdb_key_creates_total").increment(1);
+    record_duration("new_key", started);
+
     Ok(Json(KeyCreateResponse {
         name: row.name,
         name_readonly: row.roname,
@@ -91,11 +134,12 @@ pub async fn get_key_handler(
     State(app_state): State<AppState>,
     Query(input): Query<KeyGetInput>,
 ) -> Result<Json<KeyGetResponse>, Response> {
+    let started = Instant::now();
     let pool = &app_state.pool;
 
     let row = sqlx::query_as::<_, Key>(
         r#"
-        SELECT name, roname, value, last_accessed FROM keys WHERE name = $1 OR roname = $1
+        SELECT name, roname, value, last_accessed, version, siblings FROM keys WHERE name = $1 OR roname = $1
         "#)
     .bind(&input.name)
     .fetch_one(pool)
@@ -129,17 +173,37 @@ pub async fn get_key_handler(
         ).into_response()
     })?;
 
+    let raw_siblings: Vec<String> = serde_json::from_value(row.siblings).unwrap_or_default();
+    let value = decrypt_if_enabled(&app_state, row.value)?;
+    let siblings = raw_siblings
+        .into_iter()
+        .map(|sibling| decrypt_if_enabled(&app_state, sibling))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    metrics::counter!("kvdb_key_gets_total").increment(1);
+    record_duration("get_key", started);
+
     Ok(Json(KeyGetResponse {
-        value: row.value,
+        value,
+        version: row.version,
+        siblings,
         success: true,
     }))
 }
 
 // Handler to update a key's value by name. Accepts JSON input with 'name' and 'value'.
+//
+// Optimistic concurrency: when `causality` is omitted the write always overwrites
+// (last-writer-wins), preserving the original behavior for simple clients. When
+// present, the update only applies if `causality` still matches the key's current
+// version. If it doesn't - another write landed first from the same ancestor - the
+// new value is kept as a sibling instead of being discarded, and 409 Conflict is
+// returned with the current value/version/siblings so the client can merge and retry.
 pub async fn update_key_handler(
     State(app_state): State<AppState>,
     Json(input): Json<KeyUpdateInput>,
 ) -> Result<Json<KeyUpdateResponse>, Response> {
+    let started = Instant::now();
     let pool = &app_state.pool;
 
     // Check if value is shorter than the maximum allowed length
@@ -153,14 +217,61 @@ pub async fn update_key_handler(
         ).into_response());
     }
 
-    let result = sqlx::query(
+    let encrypted_value = encrypt_if_enabled(&app_state, input.value.clone());
+
+    let new_version: Option<(i64,)> = match input.causality {
+        None => sqlx::query_as::<_, (i64,)>(
+            r#"
+            UPDATE keys SET value = $1, version = version + 1, siblings = '[]'::jsonb, last_accessed = $2
+            WHERE name = $3
+            RETURNING version
+            "#)
+            .bind(&encrypted_value)
+            .bind(Utc::now())
+            .bind(&input.name)
+            .fetch_optional(pool)
+            .await,
+        Some(expected) => sqlx::query_as::<_, (i64,)>(
+            r#"
+            UPDATE keys SET value = $1, version = version + 1, siblings = '[]'::jsonb, last_accessed = $2
+            WHERE name = $3 AND version = $4
+            RETURNING version
+            "#)
+            .bind(&encrypted_value)
+            .bind(Utc::now())
+            .bind(&input.name)
+            .bind(expected)
+            .fetch_optional(pool)
+            .await,
+    }
+    .map_err(|e| {
+        (
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: e.to_string(),
+                success: false,
+            })
+        ).into_response()
+    })?;
+
+    if let Some((version,)) = new_version {
+        metrics::counter!("kvdb_key_updates_total").increment(1);
+        record_duration("update_key", started);
+        return Ok(Json(KeyUpdateResponse {
+            version,
+            success: true,
+        }));
+    }
+
+    // The conditional update matched nothing: either the key doesn't exist, or
+    // `causality` is stale. Tell the two apart, and on staleness merge the new
+    // value in as a sibling rather than silently dropping it.
+    let current = sqlx::query_as::<_, Key>(
         r#"
-        UPDATE keys SET value = $1, last_accessed = $2 WHERE name = $3
-        "#    )
-    .bind(&input.value)
-    .bind(Utc::now())
+        SELECT name, roname, value, last_accessed, version, siblings FROM keys WHERE name = $1
+        "#)
     .bind(&input.name)
-    .execute(pool)
+    .fetch_optional(pool)
     .await
     .map_err(|e| {
         (
@@ -172,8 +283,19 @@ pub async fn update_key_handler(
         ).into_response()
     })?;
 
-    // Check if the update was successful
-    if result.rows_affected() == 0 {
+    let Some(current) = current else {
+        return Err((
+            axum::http::StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: format!("Key '{}' not found or read-only key used", &input.name),
+                success: false,
+            })
+        ).into_response());
+    };
+
+    if input.causality.is_none() {
+        // Last-writer-wins path found no row on its unconditional UPDATE, and the
+        // row exists now - a race with a concurrent insert/delete. Surface as not found.
         return Err((
             axum::http::StatusCode::NOT_FOUND,
             Json(ErrorResponse {
@@ -183,9 +305,34 @@ pub async fn update_key_handler(
         ).into_response());
     }
 
-    Ok(Json(KeyUpdateResponse {
-        success: true,
-    }))
+    let row = sqlx::query_as::<_, Key>(
+        r#"
+        UPDATE keys SET siblings = siblings || to_jsonb($1::text) WHERE name = $2
+        RETURNING name, roname, value, last_accessed, version, siblings
+        "#)
+    .bind(&encrypted_value)
+    .bind(&input.name)
+    .fetch_one(pool)
+    .await
+    .unwrap_or(current);
+
+    let raw_siblings: Vec<String> = serde_json::from_value(row.siblings).unwrap_or_default();
+    let value = decrypt_if_enabled(&app_state, row.value)?;
+    let siblings = raw_siblings
+        .into_iter()
+        .map(|sibling| decrypt_if_enabled(&app_state, sibling))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Err((
+        axum::http::StatusCode::CONFLICT,
+        Json(KeyConflictResponse {
+            error: "Key was modified concurrently; value recorded as a sibling".to_string(),
+            value,
+            version: row.version,
+            siblings,
+            success: false,
+        })
+    ).into_response())
 }
 
 // Handler to delete a key by name
@@ -193,6 +340,7 @@ pub async fn delete_key_handler(
     State(app_state): State<AppState>,
     Query(input): Query<KeyDeleteInput>,
 ) -> Result<Json<KeyDeleteResponse>, Response> {
+    let started = Instant::now();
     let pool = &app_state.pool;
 
     let row = sqlx::query(
@@ -223,11 +371,358 @@ pub async fn delete_key_handler(
         ).into_response());
     }
 
+    metrics::counter!("kvdb_key_deletes_total").increment(1);
+    record_duration("delete_key", started);
+
     Ok(Json(KeyDeleteResponse {
         success: true,
     }))
 }
 
+// Handler to create multiple keys in a single request. Validates every item up front
+// so a bad name/value rejects the whole batch instead of leaving it half-applied,
+// then inserts all rows in one statement inside a transaction.
+#[axum::debug_handler]
+pub async fn new_key_batch_handler(
+    State(app_state): State<AppState>,
+    Json(inputs): Json<Vec<KeyCreateInput>>,
+) -> Result<Json<Vec<KeyCreateResponse>>, Response> {
+    let pool = &app_state.pool;
+
+    for input in &inputs {
+        if let Some(value) = &input.value {
+            if value.len() > app_state.config.max_value_length {
+                return Err((
+                    axum::http::StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse {
+                        error: format!("Value exceeds maximum length of {} characters", app_state.config.max_value_length),
+                        success: false,
+                    })
+                ).into_response());
+            }
+        }
+
+        for (name, key_type) in [(&input.name, "Key"), (&input.name_readonly, "Read-only")] {
+            if let Some(name) = name {
+                if name.len() > app_state.config.max_key_name_length || !is_valid_key_name(name) {
+                    return Err((
+                        axum::http::StatusCode::BAD_REQUEST,
+                        Json(ErrorResponse {
+                            error: format!(
+                                "{} key name is invalid or exceeds maximum length of {} characters",
+                                key_type,
+                                app_state.config.max_key_name_length
+                            ),
+                            success: false,
+                        })
+                    ).into_response());
+                }
+            }
+        }
+    }
+
+    let names: Vec<String> = inputs.iter().map(|input| get_name_or_generate(&input.name)).collect();
+    let ronames: Vec<String> = inputs.iter().map(|input| get_name_or_generate(&input.name_readonly)).collect();
+    let values: Vec<String> = inputs.iter()
+        .map(|input| encrypt_if_enabled(&app_state, input.value.clone().unwrap_or_default()))
+        .collect();
+    let now = Utc::now();
+
+    let mut tx = pool.begin().await.map_err(|e| {
+        (
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: e.to_string(),
+                success: false,
+            })
+        ).into_response()
+    })?;
+
+    let rows = sqlx::query_as::<_, Key>(
+        r#"
+        INSERT INTO keys (name, roname, value, last_accessed)
+        SELECT * FROM UNNEST($1::text[], $2::text[], $3::text[], $4::timestamptz[])
+        RETURNING name, roname, value, last_accessed, version, siblings
+        "#
+    )
+    .bind(&names)
+    .bind(&ronames)
+    .bind(&values)
+    .bind(vec![now; names.len()])
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(|e| {
+        (
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: e.to_string(),
+                success: false,
+            })
+        ).into_response()
+    })?;
+
+    tx.commit().await.map_err(|e| {
+        (
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: e.to_string(),
+                success: false,
+            })
+        ).into_response()
+    })?;
+
+    metrics::counter!("kvdb_key_creates_total").increment(rows.len() as u64);
+
+    Ok(Json(rows.into_iter().map(|row| KeyCreateResponse {
+        name: row.name,
+        name_readonly: row.roname,
+        success: true,
+    }).collect()))
+}
+
+// Handler to read multiple keys by name in a single request. Missing keys are reported
+// as a per-item failure instead of failing the whole batch.
+pub async fn read_key_batch_handler(
+    State(app_state): State<AppState>,
+    Json(names): Json<Vec<String>>,
+) -> Result<Json<BatchReadResponse>, Response> {
+    let pool = &app_state.pool;
+
+    let rows = sqlx::query_as::<_, Key>(
+        r#"
+        SELECT name, roname, value, last_accessed, version, siblings FROM keys WHERE name = ANY($1) OR roname = ANY($1)
+        "#)
+    .bind(&names)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        (
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: e.to_string(),
+                success: false,
+            })
+        ).into_response()
+    })?;
+
+    if !rows.is_empty() {
+        sqlx::query(
+            r#"
+            UPDATE keys SET last_accessed = $1 WHERE name = ANY($2) OR roname = ANY($2)
+            "#)
+        .bind(Utc::now())
+        .bind(&names)
+        .execute(pool)
+        .await
+        .map_err(|e| {
+            (
+                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: e.to_string(),
+                    success: false,
+                })
+            ).into_response()
+        })?;
+    }
+
+    let mut results: HashMap<String, BatchItemResult> = names
+        .iter()
+        .map(|name| (name.clone(), BatchItemResult { value: None, success: false }))
+        .collect();
+
+    for row in rows {
+        // A corrupted/tampered value fails closed as a per-item miss rather than
+        // aborting the whole batch or leaking why it failed.
+        let item = match decrypt_if_enabled(&app_state, row.value) {
+            Ok(value) => BatchItemResult { value: Some(value), success: true },
+            Err(_) => BatchItemResult { value: None, success: false },
+        };
+
+        // A row can satisfy either the writable name or the read-only name that was requested
+        if let Some(entry) = results.get_mut(&row.name) {
+            *entry = BatchItemResult { value: item.value.clone(), success: item.success };
+        }
+        if let Some(entry) = results.get_mut(&row.roname) {
+            *entry = BatchItemResult { value: item.value.clone(), success: item.success };
+        }
+    }
+
+    let found = results.values().filter(|item| item.success).count() as u64;
+    metrics::counter!("kvdb_key_gets_total").increment(found);
+
+    Ok(Json(BatchReadResponse { results }))
+}
+
+// Handler to delete multiple keys by name in a single request.
+pub async fn delete_key_batch_handler(
+    State(app_state): State<AppState>,
+    Json(names): Json<Vec<String>>,
+) -> Result<Json<BatchDeleteResponse>, Response> {
+    let pool = &app_state.pool;
+
+    let rows = sqlx::query_as::<_, (String,)>(
+        r#"
+        DELETE FROM keys WHERE name = ANY($1) RETURNING name
+        "#)
+    .bind(&names)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        (
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: e.to_string(),
+                success: false,
+            })
+        ).into_response()
+    })?;
+
+    metrics::counter!("kvdb_key_deletes_total").increment(rows.len() as u64);
+
+    Ok(Json(BatchDeleteResponse {
+        deleted: rows.into_iter().map(|(name,)| name).collect(),
+        success: true,
+    }))
+}
+
+// Handler that blocks until the named key's value changes or the timeout elapses,
+// letting clients observe updates without busy polling. Built on Postgres LISTEN/NOTIFY:
+// the keys_notify_insert/keys_notify_update triggers fire pg_notify('key_changed', name), a dedicated
+// PgListener forwards that onto a broadcast channel, and this handler races a
+// subscription for that name against a timeout.
+pub async fn poll_key_handler(
+    State(app_state): State<AppState>,
+    Query(input): Query<KeyPollInput>,
+) -> Result<Json<KeyPollResponse>, Response> {
+    let timeout_ms = input.timeout_ms.unwrap_or(DEFAULT_POLL_TIMEOUT_MS).min(MAX_POLL_TIMEOUT_MS);
+    let mut rx = app_state.key_change_tx.subscribe();
+
+    let changed = tokio::select! {
+        result = wait_for_key_change(&mut rx, &input.name) => result,
+        _ = tokio::time::sleep(Duration::from_millis(timeout_ms)) => false,
+    };
+
+    if !changed {
+        return Ok(Json(KeyPollResponse {
+            value: String::new(),
+            changed: false,
+            success: true,
+        }));
+    }
+
+    let row = sqlx::query_as::<_, Key>(
+        r#"
+        SELECT name, roname, value, last_accessed, version, siblings FROM keys WHERE name = $1 OR roname = $1
+        "#)
+    .bind(&input.name)
+    .fetch_one(&app_state.pool)
+    .await
+    .map_err(|e| {
+        (
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: e.to_string(),
+                success: false,
+            })
+        ).into_response()
+    })?;
+
+    Ok(Json(KeyPollResponse {
+        value: decrypt_if_enabled(&app_state, row.value)?,
+        changed: true,
+        success: true,
+    }))
+}
+
+// Waits on the broadcast channel until a notification for `name` arrives. A lagged
+// receiver is treated conservatively as a change, since we can no longer be sure we
+// didn't miss the relevant notification.
+async fn wait_for_key_change(rx: &mut broadcast::Receiver<String>, name: &str) -> bool {
+    loop {
+        match rx.recv().await {
+            Ok(changed_name) if changed_name == name => return true,
+            Ok(_) => continue,
+            Err(broadcast::error::RecvError::Lagged(_)) => return true,
+            Err(broadcast::error::RecvError::Closed) => return false,
+        }
+    }
+}
+
+// Handler to enumerate key names matching a prefix, paginated via a keyset cursor
+// (`name > after`) rather than OFFSET so deep pages stay cheap. Also returns the
+// total number of keys matching the prefix, independent of the page being returned.
+pub async fn list_keys_handler(
+    State(app_state): State<AppState>,
+    Query(input): Query<KeyListInput>,
+) -> Result<Json<KeyListResponse>, Response> {
+    let pool = &app_state.pool;
+
+    let prefix = input.prefix.unwrap_or_default();
+    if prefix.len() > app_state.config.max_key_name_length {
+        return Err((
+            axum::http::StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: format!("Prefix exceeds maximum length of {} characters", app_state.config.max_key_name_length),
+                success: false,
+            })
+        ).into_response());
+    }
+
+    let limit = input.limit.unwrap_or(DEFAULT_LIST_LIMIT).clamp(1, MAX_LIST_LIMIT);
+    let after = input.after.unwrap_or_default();
+    let pattern = format!("{}%", escape_like_pattern(&prefix));
+
+    let names: Vec<(String,)> = sqlx::query_as(
+        r#"
+        SELECT name FROM keys
+        WHERE (name LIKE $1 ESCAPE '\' OR roname LIKE $1 ESCAPE '\') AND name > $2
+        ORDER BY name
+        LIMIT $3
+        "#)
+    .bind(&pattern)
+    .bind(&after)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        (
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: e.to_string(),
+                success: false,
+            })
+        ).into_response()
+    })?;
+
+    let total: (i64,) = sqlx::query_as(
+        r#"
+        SELECT COUNT(*) FROM keys WHERE name LIKE $1 ESCAPE '\' OR roname LIKE $1 ESCAPE '\'
+        "#)
+    .bind(&pattern)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| {
+        (
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: e.to_string(),
+                success: false,
+            })
+        ).into_response()
+    })?;
+
+    Ok(Json(KeyListResponse {
+        names: names.into_iter().map(|(name,)| name).collect(),
+        total: total.0,
+        success: true,
+    }))
+}
+
+// Escapes LIKE metacharacters in a user-supplied prefix so it's matched literally.
+fn escape_like_pattern(input: &str) -> String {
+    input.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
 // Function to check validity of key name.
 // It must be alphanumeric and can contain underscores, dashes, and dots.
 pub fn is_valid_key_name(name: &str) -> bool {
@@ -1,9 +1,12 @@
 mod db;
 mod models;
 mod handlers;
+mod telemetry;
+mod crypto;
 
-use db::{create_pool, get_environment_variable, get_environment_variable_or_default, delete_unused_keys};
+use db::{create_pool, get_environment_variable, get_environment_variable_or_default, get_optional_environment_variable, delete_unused_keys, spawn_key_change_listener};
 use models::{AppConfig, AppState};
+use telemetry::{metrics_handler, spawn_keys_total_gauge};
 
 use std::{net::SocketAddr, sync::Arc};
 use axum::{http::Method, response::IntoResponse, routing::post, Json, Router};
@@ -16,6 +19,7 @@ use utoipa_swagger_ui::SwaggerUi;
 use tower_http::cors::{CorsLayer, Any};
 use axum::http::{HeaderValue};
 use tower_http::cors::{AllowOrigin};
+use base64::Engine;
 
 #[tokio::main]
 async fn main() {
@@ -45,6 +49,16 @@ async fn main() {
         max_key_name_length: get_environment_variable("MAX_KEY_NAME_LENGTH")
             .parse()
             .expect("Invalid MAX_KEY_NAME_LENGTH value"),
+        metrics_enabled: get_environment_variable_or_default("METRICS_ENABLED", "false")
+            .parse()
+            .expect("Invalid METRICS_ENABLED value"),
+        encryption_key: get_optional_environment_variable("ENCRYPTION_KEY").map(|encoded| {
+            let bytes = base64::engine::general_purpose::STANDARD
+                .decode(encoded)
+                .expect("ENCRYPTION_KEY must be valid base64");
+            <[u8; 32]>::try_from(bytes.as_slice())
+                .expect("ENCRYPTION_KEY must decode to exactly 32 bytes")
+        }),
     };
 
     let pool: PgPool = create_pool(&app_config.database_url).await;
@@ -61,6 +75,7 @@ async fn main() {
             .burst_size(app_config.rate_limit_burst_size)
             .error_handler(|err| {
                 eprintln!("Rate limit error: {}", err);
+                metrics::counter!("kvdb_rate_limit_rejections_total").increment(1);
                 (
                     axum::http::StatusCode::TOO_MANY_REQUESTS,
                     Json(models::ErrorResponse {
@@ -146,10 +161,24 @@ async fn main() {
             ))
     };
 
+    // Feeds the /key/poll long-poll handler; capacity is generous since lagging
+    // receivers just miss intermediate notifications and re-read the row on wake.
+    let (key_change_tx, _) = tokio::sync::broadcast::channel(1024);
+    spawn_key_change_listener(&app_config.database_url, key_change_tx.clone()).await;
+
+    let metrics_handle = if app_config.metrics_enabled {
+        spawn_keys_total_gauge(pool.clone()).await;
+        Some(telemetry::install_recorder())
+    } else {
+        None
+    };
+
     // Create the Axum application with the rate limiter
     let app_state = AppState {
         config: app_config.clone(),
         pool: pool.clone(),
+        key_change_tx,
+        metrics_handle,
     };
 
     // Define OpenAPI documentation
@@ -160,6 +189,11 @@ async fn main() {
             get_key_handler,
             update_key_handler,
             delete_key_handler,
+            new_key_batch_handler,
+            read_key_batch_handler,
+            delete_key_batch_handler,
+            poll_key_handler,
+            list_keys_handler,
         ),
         components(
             schemas(models::ErrorResponse)
@@ -172,6 +206,8 @@ async fn main() {
 
     let api = ApiDoc::openapi();
 
+    let metrics_app_state = app_state.clone();
+
     let app = Router::new()
         .route("/key",
             post(new_key_handler)
@@ -179,17 +215,26 @@ async fn main() {
             .patch(update_key_handler)
             .delete(delete_key_handler)
         )
+        .route("/key/batch", post(new_key_batch_handler))
+        .route("/key/batch/read", post(read_key_batch_handler))
+        .route("/key/batch/delete", post(delete_key_batch_handler))
+        .route("/key/poll", axum::routing::get(poll_key_handler))
+        .route("/key/list", axum::routing::get(list_keys_handler))
         .route("/health", axum::routing::get(|| async { "OK" }))
         .with_state(app_state)
         .layer(GovernorLayer {
             config: rate_limit_config,
         })
         .layer(cors)
+        // Mounted after the CORS layer, like the Swagger UI below, so /metrics is
+        // never subject to the write-oriented CORS method allowlist.
+        .merge(Router::new().route("/metrics", axum::routing::get(metrics_handler)).with_state(metrics_app_state))
         .merge(SwaggerUi::new("/").url("/api-doc/openapi.json", api));
 
     // Print app configuration without sensitive data (like database URL)
     let mut sanitized_config = app_config.clone();
     sanitized_config.database_url = "REDACTED".to_string(); // Redact sensitive data
+    sanitized_config.encryption_key = sanitized_config.encryption_key.map(|_| [0u8; 32]); // Redact sensitive data
     println!("Starting server with configuration: {:#?}", sanitized_config);
 
     let listener = tokio::net::TcpListener::bind(app_config.listen_on).await.unwrap();
@@ -13,12 +13,25 @@ pub struct AppConfig {
     pub delete_unused_keys_after: String,
     pub max_value_length: usize,
     pub max_key_name_length: usize,
+    pub metrics_enabled: bool,
+    /// When set (from base64-encoded `ENCRYPTION_KEY`), values are AES-256-GCM
+    /// encrypted before being stored and decrypted after being read. When unset,
+    /// behavior is unchanged so existing plaintext rows keep working. Note this is
+    /// a one-way toggle: turning it on after rows already hold plaintext values
+    /// makes those rows fail to decrypt, since there's no plaintext fallback path.
+    pub encryption_key: Option<[u8; 32]>,
 }
 
 #[derive(Debug, Clone)]
 pub struct AppState {
     pub config: AppConfig,
     pub pool: sqlx::PgPool,
+    /// Broadcasts the name of every key that was inserted or updated, fed by a
+    /// dedicated `PgListener` on the `key_changed` channel. Used by the `/key/poll`
+    /// long-poll handler to wake up without busy-polling the database.
+    pub key_change_tx: tokio::sync::broadcast::Sender<String>,
+    /// `Some` when `METRICS_ENABLED` is `true`, renders the current Prometheus snapshot for `/metrics`.
+    pub metrics_handle: Option<metrics_exporter_prometheus::PrometheusHandle>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -27,6 +40,11 @@ pub struct Key {
     pub roname: String,
     pub value: String,
     pub last_accessed: DateTime<Utc>,
+    /// Monotonic causality token. Bumped on every write that isn't a sibling branch.
+    pub version: i64,
+    /// Concurrent values that raced against each other from the same ancestor version.
+    /// Collapsed back to a single value by the next write carrying the current `version`.
+    pub siblings: serde_json::Value,
 }
 
 #[derive(Debug, Deserialize)]
@@ -51,6 +69,10 @@ pub struct KeyGetInput {
 #[derive(Debug, Serialize)]
 pub struct KeyGetResponse {
     pub value: String,
+    /// Causality token to echo back in `KeyUpdateInput::causality` on the next write.
+    pub version: i64,
+    /// Present when concurrent writes raced each other and couldn't be merged automatically.
+    pub siblings: Vec<String>,
     pub success: bool,
 }
 
@@ -58,10 +80,27 @@ pub struct KeyGetResponse {
 pub struct KeyUpdateInput {
     pub name: String,
     pub value: String,
+    /// The version this write is based on. Omit for last-writer-wins behavior
+    /// (preserves the pre-existing behavior for simple clients). When present, the
+    /// write is rejected with 409 Conflict if the key has moved on, and the value is
+    /// instead recorded as a sibling alongside the current value.
+    pub causality: Option<i64>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct KeyUpdateResponse {
+    pub version: i64,
+    pub success: bool,
+}
+
+/// Returned with 409 Conflict when `causality` is stale. The write was kept as a
+/// sibling rather than discarded; `value`/`siblings` reflect the key after that merge.
+#[derive(Debug, Serialize)]
+pub struct KeyConflictResponse {
+    pub error: String,
+    pub value: String,
+    pub version: i64,
+    pub siblings: Vec<String>,
     pub success: bool,
 }
 
@@ -80,3 +119,47 @@ pub struct ErrorResponse {
     pub error: String,
     pub success: bool
 }
+
+#[derive(Debug, Serialize)]
+pub struct BatchItemResult {
+    pub value: Option<String>,
+    pub success: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchReadResponse {
+    pub results: std::collections::HashMap<String, BatchItemResult>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchDeleteResponse {
+    pub deleted: Vec<String>,
+    pub success: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct KeyListInput {
+    pub prefix: Option<String>,
+    pub limit: Option<i64>,
+    pub after: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct KeyListResponse {
+    pub names: Vec<String>,
+    pub total: i64,
+    pub success: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct KeyPollInput {
+    pub name: String,
+    pub timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct KeyPollResponse {
+    pub value: String,
+    pub changed: bool,
+    pub success: bool,
+}
@@ -0,0 +1,46 @@
+use std::time::{Duration, Instant};
+
+use axum::{extract::State, response::{IntoResponse, Response}};
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use sqlx::PgPool;
+
+use crate::models::AppState;
+
+/// Installs the global Prometheus recorder used by every `metrics::counter!`/`histogram!`/
+/// `gauge!` call in the handlers, and returns a handle that renders the current
+/// snapshot for the `/metrics` endpoint.
+pub fn install_recorder() -> PrometheusHandle {
+    PrometheusBuilder::new()
+        .install_recorder()
+        .expect("Failed to install Prometheus metrics recorder")
+}
+
+/// Records a handler's happy-path latency under the `kvdb_handler_duration_seconds` histogram.
+pub fn record_duration(handler: &'static str, started: Instant) {
+    metrics::histogram!("kvdb_handler_duration_seconds", "handler" => handler)
+        .record(started.elapsed().as_secs_f64());
+}
+
+/// Periodically refreshes the `kvdb_keys_total` gauge from `SELECT COUNT(*)`, since
+/// tracking it incrementally across single, batch, and cleanup deletes would be
+/// error-prone to keep in sync.
+pub async fn spawn_keys_total_gauge(pool: PgPool) {
+    tokio::spawn(async move {
+        loop {
+            match sqlx::query_as::<_, (i64,)>("SELECT COUNT(*) FROM keys").fetch_one(&pool).await {
+                Ok((count,)) => metrics::gauge!("kvdb_keys_total").set(count as f64),
+                Err(e) => eprintln!("Failed to refresh kvdb_keys_total gauge: {}", e),
+            }
+            tokio::time::sleep(Duration::from_secs(15)).await;
+        }
+    });
+}
+
+// Handler serving the Prometheus text exposition format. Returns 404 when metrics
+// are disabled via METRICS_ENABLED so operators can opt out entirely.
+pub async fn metrics_handler(State(app_state): State<AppState>) -> Response {
+    match &app_state.metrics_handle {
+        Some(handle) => handle.render().into_response(),
+        None => (axum::http::StatusCode::NOT_FOUND, "Metrics are disabled").into_response(),
+    }
+}